@@ -0,0 +1,75 @@
+use anyhow::{format_err, Result};
+use std::{fmt, path::Path, str::FromStr};
+
+/// Whether this architecture boots via EFI. x86_64 and aarch64 systems can
+/// also be booted in legacy BIOS mode, so this doesn't fully determine the
+/// boot mode on its own; see [`resolve`].
+pub(crate) const ARCH_USES_EFI: bool = cfg!(any(target_arch = "x86_64", target_arch = "aarch64"));
+
+/// Which firmware interface to install a bootloader for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BootMode {
+    Efi,
+    Bios,
+
+    /// Detect the mode based on how the installer itself was booted.
+    Auto,
+}
+
+impl FromStr for BootMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "efi" => Self::Efi,
+            "bios" => Self::Bios,
+            "auto" => Self::Auto,
+            _ => return Err(format_err!("unknown boot mode: {}", s)),
+        })
+    }
+}
+
+impl fmt::Display for BootMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Efi => write!(f, "efi"),
+            Self::Bios => write!(f, "bios"),
+            Self::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Resolve `Auto` to a concrete `Efi`/`Bios` mode by checking the
+/// architecture and the running system's firmware.
+pub(crate) fn resolve(mode: BootMode) -> BootMode {
+    match mode {
+        BootMode::Auto => {
+            if ARCH_USES_EFI && Path::new("/sys/firmware/efi/efivars").exists() {
+                BootMode::Efi
+            } else {
+                BootMode::Bios
+            }
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boot_mode_from_str() {
+        assert_eq!(BootMode::from_str("efi").unwrap(), BootMode::Efi);
+        assert_eq!(BootMode::from_str("bios").unwrap(), BootMode::Bios);
+        assert_eq!(BootMode::from_str("auto").unwrap(), BootMode::Auto);
+        assert!(BootMode::from_str("uefi").is_err());
+    }
+
+    #[test]
+    fn test_boot_mode_display_round_trips() {
+        for mode in [BootMode::Efi, BootMode::Bios, BootMode::Auto] {
+            assert_eq!(BootMode::from_str(&mode.to_string()).unwrap(), mode);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{exec, exec_with_stdin, Device};
+use anyhow::{format_err, Result};
+use std::{fmt, str::FromStr};
+
+/// Which (if any) encryption scheme to apply to the root filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EncryptMode {
+    /// Don't encrypt anything.
+    None,
+
+    /// Wrap the root partition in LUKS before formatting it.
+    Luks,
+
+    /// Use the root filesystem's own native encryption instead of LUKS.
+    /// Currently only supported by ZFS.
+    ZfsNative,
+}
+
+impl FromStr for EncryptMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "none" => Self::None,
+            "luks" => Self::Luks,
+            "zfs-native" => Self::ZfsNative,
+            _ => return Err(format_err!("unknown encrypt mode: {}", s)),
+        })
+    }
+}
+
+impl fmt::Display for EncryptMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Luks => write!(f, "luks"),
+            Self::ZfsNative => write!(f, "zfs-native"),
+        }
+    }
+}
+
+/// A LUKS-encrypted block device, unlocked at `/dev/mapper/<name>`.
+pub(crate) struct Luks;
+
+impl Luks {
+    /// `luksFormat` and open `partition`, returning the unlocked mapper
+    /// device that should be used as the format target instead of the raw
+    /// partition.
+    pub(crate) fn open(partition: &Device, name: &str, passphrase: &str) -> Result<Device> {
+        if !exec_with_stdin(
+            &["cryptsetup", "luksFormat", "--batch-mode", &partition.dev()],
+            passphrase,
+        )?
+        .status
+        .success()
+        {
+            return Err(format_err!("error luks-formatting {}", partition.dev()));
+        }
+
+        if !exec_with_stdin(&["cryptsetup", "open", &partition.dev(), name], passphrase)?
+            .status
+            .success()
+        {
+            return Err(format_err!(
+                "error opening luks mapping on {}",
+                partition.dev()
+            ));
+        }
+
+        Ok(Device::from_path(format!("/dev/mapper/{}", name)))
+    }
+
+    /// Close a mapping opened with [`Luks::open`].
+    pub(crate) fn close(name: &str) -> Result<()> {
+        if !exec(&["cryptsetup", "close", name])?.status.success() {
+            return Err(format_err!("error closing luks mapping {}", name));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_mode_from_str() {
+        assert_eq!(EncryptMode::from_str("none").unwrap(), EncryptMode::None);
+        assert_eq!(EncryptMode::from_str("luks").unwrap(), EncryptMode::Luks);
+        assert_eq!(
+            EncryptMode::from_str("zfs-native").unwrap(),
+            EncryptMode::ZfsNative
+        );
+        assert!(EncryptMode::from_str("aes").is_err());
+    }
+
+    #[test]
+    fn test_encrypt_mode_display_round_trips() {
+        for mode in [EncryptMode::None, EncryptMode::Luks, EncryptMode::ZfsNative] {
+            assert_eq!(EncryptMode::from_str(&mode.to_string()).unwrap(), mode);
+        }
+    }
+}
@@ -1,62 +1,165 @@
-use anyhow::Result;
+use anyhow::{format_err, Context, Result};
 use log::{debug, error, info, warn};
+use serde::Deserialize;
 use std::{
     fs,
     path::{Path, PathBuf},
 };
 
+/// GPT partition type GUID for an EFI System Partition.
+pub(crate) const ESP_TYPE_GUID: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+
+/// GPT partition type GUID for a generic Linux filesystem.
+pub(crate) const LINUX_TYPE_GUID: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+
 #[derive(Debug)]
 pub(crate) struct Device {
     pub(crate) name: String,
     pub(crate) bytes: usize,
+
+    /// The device's full path, e.g. `/dev/sda` or `/dev/mapper/cryptroot`.
+    /// Kept separately from `name` (which is just the last path component,
+    /// used for display) so that `dev()` can round-trip nested paths
+    /// verbatim instead of reconstructing them under `/dev/`.
+    path: PathBuf,
+}
+
+/// A single partition as reported by `sfdisk --json`.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Partition {
+    /// Path to the partition's device node, e.g. `/dev/sda1`.
+    pub(crate) node: PathBuf,
+
+    /// 1-based partition number within the table. Derived from `node`
+    /// after deserializing, since sfdisk doesn't report it directly.
+    #[serde(skip)]
+    pub(crate) partno: u32,
+
+    pub(crate) start: u64,
+    pub(crate) size: u64,
+
+    /// GPT partition type GUID.
+    #[serde(rename = "type")]
+    pub(crate) type_guid: String,
+}
+
+impl Partition {
+    /// Treat this partition as a `Device` so it can be passed to the
+    /// filesystem init routines.
+    pub(crate) fn as_device(&self) -> Device {
+        Device::from_path(&self.node)
+    }
+}
+
+/// The partition table for a [`Device`], as reported by `sfdisk --json`.
+#[derive(Debug, Clone)]
+pub(crate) struct PartitionTable {
+    pub(crate) device: String,
+    pub(crate) partitions: Vec<Partition>,
+}
+
+impl PartitionTable {
+    /// Find a partition by its 1-based partition number.
+    pub(crate) fn find_partno(&self, n: u32) -> Result<&Partition> {
+        self.partitions
+            .iter()
+            .find(|p| p.partno == n)
+            .ok_or_else(|| format_err!("missing partition for index {}", n))
+    }
+
+    /// Find the EFI System Partition by its GPT type GUID. Unlike
+    /// `find_partno`, this doesn't assume any particular partition number,
+    /// so it also works on a foreign partition table (e.g. `Alongside`
+    /// mode).
+    pub(crate) fn find_esp(&self) -> Result<&Partition> {
+        self.partitions
+            .iter()
+            .find(|p| p.type_guid.eq_ignore_ascii_case(ESP_TYPE_GUID))
+            .ok_or_else(|| format_err!("no EFI System Partition found on {}", self.device))
+    }
+
+    /// Find the partition most likely to hold an existing root filesystem:
+    /// the largest partition with a Linux filesystem type GUID. Used as a
+    /// fallback when nothing is mounted for us to corroborate against.
+    pub(crate) fn find_largest_linux_partition(&self) -> Result<&Partition> {
+        self.partitions
+            .iter()
+            .filter(|p| p.type_guid.eq_ignore_ascii_case(LINUX_TYPE_GUID))
+            .max_by_key(|p| p.size)
+            .ok_or_else(|| format_err!("no Linux filesystem partition found on {}", self.device))
+    }
+}
+
+// Raw shape of `sfdisk --json` output, used only for deserialization.
+#[derive(Debug, Deserialize)]
+struct SfdiskOutput {
+    partitiontable: SfdiskTable,
+}
+
+#[derive(Debug, Deserialize)]
+struct SfdiskTable {
+    device: String,
+    partitions: Vec<Partition>,
+}
+
+/// Parse the trailing partition number off a device node, e.g. `/dev/sda1`
+/// -> `1`, `/dev/nvme0n1p3` -> `3`.
+fn partno_from_node(node: &Path) -> Result<u32> {
+    let name = node
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format_err!("invalid partition node {:?}", node))?;
+
+    let digits: String = name.chars().rev().take_while(|c| c.is_ascii_digit()).collect();
+    let digits: String = digits.chars().rev().collect();
+
+    digits
+        .parse()
+        .with_context(|| format!("parsing partition number from {:?}", node))
 }
 
 impl Device {
     pub(crate) fn dev(&self) -> String {
-        format!("/dev/{}", self.name)
+        self.path.to_string_lossy().into_owned()
     }
 
     pub(crate) fn from_path<T: AsRef<Path>>(path: T) -> Self {
+        let path = path.as_ref();
+
         Self {
-            name: path
-                .as_ref()
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string(),
+            name: path.file_name().unwrap().to_str().unwrap().to_string(),
             bytes: 0,
+            path: path.to_path_buf(),
         }
     }
 
-    pub(crate) fn partitions(&self) -> Result<Vec<Self>> {
-        // Get the device id for this device.
-        let id =
-            fs::read_to_string(PathBuf::from("/sys/block/").join(&self.name).join("dev"))?.trim();
-
-        // Read the symlink to the device location.
-        let path = fs::read_link(
-            PathBuf::from("/sys/class/block")
-                .join(&self.name)
-                .join("subsystem"),
-        )?;
-
-        // Grab all the partitions for the device.
-        let mut partitions = vec![];
-        for entry in path.read_dir()? {
-            let path = entry?.path();
-            let path = fs::read_link(path)?;
-
-            let partition: usize = match fs::read_to_string(path) {
-                Ok(p) => p.trim().parse()?,
-                Err(e) => match e.kind() {
-                    std::io::ErrorKind::NotFound => continue,
-                    _ => return Err(anyhow::Error::new(e)),
-                },
-            };
+    /// Read this device's partition table via `sfdisk --json`.
+    pub(crate) fn partitions(&self) -> Result<PartitionTable> {
+        let dev = self.dev();
+
+        let output = crate::exec(&["sfdisk", "--json", &dev])?;
+        if !output.status.success() {
+            return Err(format_err!("error reading partition table for {}", dev));
         }
 
-        Ok(partitions)
+        let raw = std::str::from_utf8(&output.stdout)?;
+        let parsed: SfdiskOutput =
+            serde_json::from_str(raw).with_context(|| format!("parsing sfdisk output for {}", dev))?;
+
+        let partitions = parsed
+            .partitiontable
+            .partitions
+            .into_iter()
+            .map(|mut p| {
+                p.partno = partno_from_node(&p.node)?;
+                Ok(p)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(PartitionTable {
+            device: parsed.partitiontable.device,
+            partitions,
+        })
     }
 
     pub(crate) fn list() -> Result<Vec<Self>> {
@@ -87,8 +190,9 @@ impl Device {
             }
 
             let name = String::from(path.file_name().unwrap().to_str().unwrap().to_string());
+            let path = PathBuf::from("/dev").join(&name);
 
-            out.push(Self { name, bytes });
+            out.push(Self { name, bytes, path });
         }
 
         out.sort_by(|a, b| b.bytes.cmp(&a.bytes));
@@ -114,5 +218,16 @@ mod tests {
     #[test]
     fn test_device_from_path() {
         assert_eq!(Device::from_path("/dev/sda").name, "sda");
+        assert_eq!(Device::from_path("/dev/sda").dev(), "/dev/sda");
+    }
+
+    #[test]
+    fn test_device_from_path_nested() {
+        // Mapper devices (e.g. an unlocked LUKS mapping) live under a
+        // subdirectory of /dev; dev() must round-trip the full path rather
+        // than reconstructing it from just the last component.
+        let device = Device::from_path("/dev/mapper/cryptroot");
+        assert_eq!(device.name, "cryptroot");
+        assert_eq!(device.dev(), "/dev/mapper/cryptroot");
     }
 }
@@ -14,10 +14,10 @@ impl super::Filesystem for Ext4 {
         Ok(())
     }
 
-    fn init(&self, partition: &Device) -> Result<Self> {
+    fn init(&self, partition: &Device) -> Result<()> {
         run_fun!(mkfs.ext4 $partition)?;
 
-        Ok(Self {})
+        Ok(())
     }
 }
 
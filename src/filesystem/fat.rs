@@ -6,7 +6,7 @@ use std::fmt;
 pub(crate) struct FAT32 {}
 
 impl super::Filesystem for FAT32 {
-    fn init(&self, partition: &Device) -> Result<Self> {
+    fn init(&self, partition: &Device) -> Result<()> {
         if !exec(&["mkfs.vfat", "-F32", &format!("of={}", partition.dev())])?
             .status
             .success()
@@ -17,7 +17,7 @@ impl super::Filesystem for FAT32 {
             ));
         }
 
-        Ok(Self {})
+        Ok(())
     }
 }
 
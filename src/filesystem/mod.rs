@@ -10,7 +10,7 @@ pub(crate) use ext4::Ext4;
 pub(crate) use fat::FAT32;
 pub(crate) use zfs::ZFS;
 
-pub(crate) trait Filesystem: Clone + Copy + Debug + Display {
+pub(crate) trait Filesystem: Debug + Display {
     fn cleanup(&self) -> Result<()> {
         Ok(())
     }
@@ -19,12 +19,23 @@ pub(crate) trait Filesystem: Clone + Copy + Debug + Display {
         Ok(())
     }
 
-    fn init(&self, partition: &Device) -> Result<Self>;
+    fn init(&self, partition: &Device) -> Result<()>;
+
+    /// Like `init`, but given an optional encryption passphrase. Filesystems
+    /// that support native encryption (e.g. ZFS) should use it when a
+    /// passphrase is given; others can just ignore it.
+    fn init_with_passphrase(&self, partition: &Device, _passphrase: Option<&str>) -> Result<()> {
+        self.init(partition)
+    }
 }
 
-pub(crate) fn from_str<T: AsRef<str>>(s: T) -> Result<impl Filesystem> {
+/// Look up a filesystem implementation by name. Recognizes every filesystem
+/// this installer can actually format: `zfs`, `ext4`, and `fat32`.
+pub(crate) fn from_str<T: AsRef<str>>(s: T) -> Result<Box<dyn Filesystem>> {
     Ok(match s.as_ref() {
-        "zfs" => ZFS {},
-        _ => return Err(format_err!("unknown fs".to_string())),
+        "zfs" => Box::new(ZFS {}),
+        "ext4" => Box::new(Ext4 {}),
+        "fat32" => Box::new(FAT32 {}),
+        other => return Err(format_err!("unknown filesystem: {}", other)),
     })
 }
@@ -1,4 +1,4 @@
-use crate::{exec, Device};
+use crate::{exec, exec_with_stdin, Device};
 use anyhow::{format_err, Result};
 use cmd_lib::run_fun;
 use std::fmt;
@@ -24,7 +24,7 @@ impl super::Filesystem for ZFS {
         Ok(())
     }
 
-    fn init(&self, partition: &Device) -> Result<Self> {
+    fn init(&self, partition: &Device) -> Result<()> {
         if !exec(&[
             "dd",
             "if=/dev/urandom",
@@ -59,7 +59,61 @@ impl super::Filesystem for ZFS {
             ));
         }
 
-        Ok(Self {})
+        Ok(())
+    }
+
+    fn init_with_passphrase(&self, partition: &Device, passphrase: Option<&str>) -> Result<()> {
+        let passphrase = match passphrase {
+            Some(p) => p,
+            None => return self.init(partition),
+        };
+
+        if !exec(&[
+            "dd",
+            "if=/dev/urandom",
+            &format!("of={}", partition.dev()),
+            "bs=512",
+            "count=20480",
+        ])?
+        .status
+        .success()
+        {
+            return Err(format_err!(
+                "error using dd to overwrite beginning of {}",
+                partition.dev()
+            ));
+        }
+
+        // zpool create prompts for the passphrase twice when using native
+        // encryption: once to set it, once to confirm.
+        let stdin = format!("{0}\n{0}\n", passphrase);
+
+        if !exec_with_stdin(
+            &[
+                "zpool",
+                "create",
+                "-f",
+                "zroot",
+                "-m",
+                "none",
+                "-O",
+                "encryption=aes-256-gcm",
+                "-O",
+                "keyformat=passphrase",
+                &partition.dev(),
+            ],
+            &stdin,
+        )?
+        .status
+        .success()
+        {
+            return Err(format_err!(
+                "error initializing encrypted zpool on {}",
+                partition.dev()
+            ));
+        }
+
+        Ok(())
     }
 }
 
@@ -0,0 +1,102 @@
+use anyhow::{format_err, Result};
+use std::{fmt, path::PathBuf, str::FromStr};
+
+/// How to lay out partitions on the target device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum PartitionMode {
+    /// Compute a GPT layout automatically, sized from `--boot-size`/
+    /// `--root-size`.
+    Auto,
+
+    /// Format and mount an explicit list of `--manual-partition` specs.
+    Manual,
+}
+
+impl FromStr for PartitionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "auto" => Self::Auto,
+            "manual" => Self::Manual,
+            _ => return Err(format_err!("unknown partition mode: {}", s)),
+        })
+    }
+}
+
+impl fmt::Display for PartitionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+/// A single `device:mountpoint:fstype:size` spec passed via
+/// `--manual-partition` in `Manual` partition mode. `size` is informational
+/// only in this mode: the partitions are expected to already exist, and are
+/// just formatted and mounted in order.
+#[derive(Clone, Debug)]
+pub(crate) struct ManualPartitionSpec {
+    pub(crate) device: PathBuf,
+    pub(crate) mountpoint: PathBuf,
+    pub(crate) fstype: String,
+    pub(crate) size: String,
+}
+
+impl FromStr for ManualPartitionSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split(':').collect::<Vec<&str>>().as_slice() {
+            [device, mountpoint, fstype, size] => Ok(Self {
+                device: PathBuf::from(device),
+                mountpoint: PathBuf::from(mountpoint),
+                fstype: fstype.to_string(),
+                size: size.to_string(),
+            }),
+            _ => Err(format_err!(
+                "invalid partition spec {:?}; expected device:mountpoint:fstype:size",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_mode_from_str() {
+        assert_eq!(PartitionMode::from_str("auto").unwrap(), PartitionMode::Auto);
+        assert_eq!(
+            PartitionMode::from_str("manual").unwrap(),
+            PartitionMode::Manual
+        );
+        assert!(PartitionMode::from_str("semi").is_err());
+    }
+
+    #[test]
+    fn test_partition_mode_display_round_trips() {
+        for mode in [PartitionMode::Auto, PartitionMode::Manual] {
+            assert_eq!(PartitionMode::from_str(&mode.to_string()).unwrap(), mode);
+        }
+    }
+
+    #[test]
+    fn test_manual_partition_spec_from_str() {
+        let spec = ManualPartitionSpec::from_str("/dev/sda1:/boot:fat32:2GiB").unwrap();
+        assert_eq!(spec.device, PathBuf::from("/dev/sda1"));
+        assert_eq!(spec.mountpoint, PathBuf::from("/boot"));
+        assert_eq!(spec.fstype, "fat32");
+        assert_eq!(spec.size, "2GiB");
+    }
+
+    #[test]
+    fn test_manual_partition_spec_from_str_wrong_field_count() {
+        assert!(ManualPartitionSpec::from_str("/dev/sda1:/boot:fat32").is_err());
+        assert!(ManualPartitionSpec::from_str("/dev/sda1:/boot:fat32:2GiB:extra").is_err());
+    }
+}
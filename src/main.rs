@@ -1,23 +1,34 @@
 use anyhow::{anyhow, format_err, Context, Result};
-use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password, Select};
 use log::{debug, error, info, warn};
 use simplelog::{CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode, WriteLogger};
 use std::{
     error::Error,
     fmt, fs,
+    io::Write,
     path::{Path, PathBuf},
     process,
     str::FromStr,
 };
 use structopt::StructOpt;
 
+mod boot;
+mod crypt;
 mod device;
 mod filesystem;
+mod layout;
+mod mount;
+mod replace;
 #[cfg(test)]
 mod tests;
+mod user;
 
+use boot::BootMode;
+use crypt::{EncryptMode, Luks};
 use device::Device;
 use filesystem::Filesystem;
+use layout::{ManualPartitionSpec, PartitionMode};
+use replace::ReplaceMode;
 
 #[derive(Debug, StructOpt)]
 #[structopt(setting = structopt::clap::AppSettings::ColoredHelp)]
@@ -46,6 +57,53 @@ struct Opt {
     // Path to the device to install the system on. Will prompt if not passed.
     #[structopt(short, long)]
     device: Option<PathBuf>,
+
+    /// How to handle any existing data on the target device: `wipe` the
+    /// device entirely, or install `alongside` an existing OS.
+    #[structopt(long, default_value = "wipe")]
+    replace: ReplaceMode,
+
+    /// How (if at all) to encrypt the root filesystem: `none`, `luks`, or
+    /// `zfs-native` (only valid with `--filesystem zfs`).
+    #[structopt(long, default_value = "none")]
+    encrypt: EncryptMode,
+
+    /// Which firmware interface to install a bootloader for: `efi`, `bios`,
+    /// or `auto` to detect based on how the installer itself was booted.
+    #[structopt(long, default_value = "auto")]
+    boot_mode: BootMode,
+
+    /// Pre-hashed crypt password for the default user. Prompted for
+    /// interactively if not set.
+    #[structopt(long)]
+    password_hash: Option<String>,
+
+    /// Pre-hashed crypt password for root. Prompted for interactively if
+    /// not set.
+    #[structopt(long)]
+    root_password_hash: Option<String>,
+
+    /// Whether to compute the partition layout automatically, or take an
+    /// explicit list of partitions via `--manual-partition`.
+    #[structopt(long, default_value = "auto")]
+    partition_mode: PartitionMode,
+
+    /// Size of the boot partition in `Auto` partition mode, as a `parted`
+    /// size string (e.g. `2GiB`). Only affects EFI installs; the BIOS
+    /// bios_grub partition is always small and fixed-size.
+    #[structopt(long, default_value = "2GiB")]
+    boot_size: String,
+
+    /// Size of the root partition in `Auto` partition mode, as a `parted`
+    /// size string (e.g. `100%`).
+    #[structopt(long, default_value = "100%")]
+    root_size: String,
+
+    /// A `device:mountpoint:fstype:size` partition spec, repeatable. Only
+    /// used in `Manual` partition mode, where each partition is expected to
+    /// already exist and is just formatted and mounted in order.
+    #[structopt(long)]
+    manual_partition: Vec<ManualPartitionSpec>,
 }
 
 // Set the mirrorlist for install.
@@ -53,6 +111,10 @@ struct Opt {
 const MIRRORLIST_URL: &str =
     "https://www.archlinux.org/mirrorlist/?country=US&protocol=https&use_mirror_status=on";
 
+// Name used for the LUKS mapping of the root partition, i.e. it shows up as
+// /dev/mapper/cryptroot.
+const LUKS_MAPPER_NAME: &str = "cryptroot";
+
 fn main() -> Result<()> {
     // Set up the logger to log to terminal and disk, for debugging later.
     CombinedLogger::init(vec![
@@ -73,11 +135,8 @@ fn main() -> Result<()> {
     let filesystem = if let Some(f) = opt.filesystem {
         f
     } else {
-        let options = &["zfs".to_string()];
-        let i = select(
-            "What filesystem would you like to use?",
-            &["zfs".to_string()],
-        )?;
+        let options = &["zfs".to_string(), "ext4".to_string(), "fat32".to_string()];
+        let i = select("What filesystem would you like to use?", options)?;
 
         options[i].to_string()
     };
@@ -109,6 +168,38 @@ fn main() -> Result<()> {
     };
     debug!("default user: {}", user);
 
+    let replace_mode = opt.replace;
+    debug!("replace mode: {}", replace_mode);
+
+    let encrypt = opt.encrypt;
+    debug!("encrypt mode: {}", encrypt);
+
+    if encrypt == EncryptMode::ZfsNative && filesystem.to_string() != "ZFS" {
+        return Err(format_err!("--encrypt=zfs-native requires --filesystem=zfs"));
+    }
+
+    // `Alongside` mode reuses the existing root filesystem as-is; there's
+    // nothing left to encrypt by the time we get to it, so reject the
+    // combination up front rather than silently ignoring --encrypt.
+    if replace_mode == ReplaceMode::Alongside && encrypt != EncryptMode::None {
+        return Err(format_err!(
+            "--encrypt={} is not supported with --replace=alongside",
+            encrypt
+        ));
+    }
+
+    // Prompt for the encryption passphrase up front, before we start doing
+    // anything destructive.
+    let passphrase = match encrypt {
+        EncryptMode::None => None,
+        EncryptMode::Luks | EncryptMode::ZfsNative => Some(
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Encryption passphrase")
+                .with_confirmation("Confirm passphrase", "Passphrases didn't match")
+                .interact()?,
+        ),
+    };
+
     // Select the device we're going to install the OS onto.
     let device = if let Some(d) = opt.device {
         Device::from_path(d)
@@ -127,14 +218,19 @@ fn main() -> Result<()> {
     };
     debug!("device: {:?}", device);
 
-    // Ensure the system is booted in EFI.
-    assert_efi()?;
+    let boot_mode = boot::resolve(opt.boot_mode);
+    debug!("boot mode: {}", boot_mode);
 
-    // Ensure the ZFS module is present
-    if !exec(&["modprobe", "zfs"])?.status.success() {
-        return Err(anyhow!("zfs module is not loaded"));
+    // Ensure the system is booted in EFI, if that's the mode we're installing
+    // for.
+    if boot_mode == BootMode::Efi {
+        assert_efi()?;
     }
 
+    // Make sure whatever filesystem the user picked has what it needs
+    // available (e.g. the zfs module for ZFS), rather than assuming ZFS.
+    filesystem.assert_dependencies()?;
+
     // Make sure the user understands this is going to be destructive.
     if !confirm("Installation will be destructive; continue?")? {
         error!("no confirmation received; aborting");
@@ -153,31 +249,261 @@ fn main() -> Result<()> {
         rankmirrors()?;
     }
 
+    // If a previous run left something mounted at /mnt, make sure we don't
+    // blindly wipe it out from under the user without saying anything.
+    // Nothing unmounts it here unless `--clean` is also passed, in which
+    // case `filesystem.cleanup()` below does; otherwise the partitioning and
+    // mkfs steps below are likely to fail against a busy device, so bail out
+    // instead of pressing on.
+    if let Ok(existing) = mount::inspect("/mnt") {
+        let source = existing.resolved_source()?;
+        let mounted_device = mount::find_parent_devices(source)?;
+        if mounted_device.dev() == device.dev() {
+            if !clean {
+                return Err(format_err!(
+                    "{} ({}) is already mounted at /mnt; unmount it or re-run with --clean before continuing",
+                    source,
+                    existing.fstype
+                ));
+            }
+
+            warn!(
+                "{} ({}) is already mounted at /mnt; --clean will unmount it before continuing",
+                source, existing.fstype
+            );
+        }
+    }
+
     // Clean up the system if was requested. This is mostly needed if the
     // installer has been run before unsuccessfully.
     if clean {
+        // Unmount the filesystem before closing the LUKS mapping underneath
+        // it; `cryptsetup close` fails on a mapping that's still in use.
         filesystem.cleanup()?;
+
+        if encrypt == EncryptMode::Luks {
+            Luks::close(LUKS_MAPPER_NAME)?;
+        }
     }
 
-    // Partition the disk. Make a large /boot partition so that we have room for
-    // multiple kernels, etc.
-    partition(&device)?;
+    match opt.partition_mode {
+        PartitionMode::Auto => {
+            // Partition the disk. Make a large /boot partition so that we have
+            // room for multiple kernels, etc. In `Alongside` mode this reuses
+            // the existing partition table instead.
+            partition(&device, replace_mode, boot_mode, &opt.boot_size, &opt.root_size)?;
+
+            let table = device.partitions()?;
+
+            match replace_mode {
+                ReplaceMode::Wipe => {
+                    // We just laid out this table ourselves, so the
+                    // partition numbers are known.
+                    let boot_partition = table.find_partno(1)?;
+                    let root_partition = table.find_partno(3)?;
+
+                    // Make sure they're actually what we expect before we do
+                    // anything destructive with them. In BIOS mode
+                    // partition 1 is a bios_grub partition rather than an
+                    // ESP, so skip that check.
+                    if boot_mode == BootMode::Efi {
+                        verify_partition_types(boot_partition, root_partition)?;
+                    }
+                    let part_boot = boot_partition.as_device();
+                    let part_root = root_partition.as_device();
+
+                    // Get rid of any old partition/filesystem info from the partitions.
+                    wipe(&part_boot)?;
+                    wipe(&part_root)?;
+
+                    // Install the bootloader for our firmware mode.
+                    match boot_mode {
+                        BootMode::Efi => {
+                            let fat32 = filesystem::FAT32 {};
+                            fat32.init(&part_boot)?;
+                        }
+                        BootMode::Bios => {
+                            // The bios_grub partition holds core.img directly and has
+                            // no filesystem of its own; grub-install finds it and the
+                            // target device's MBR on its own.
+                            if !exec(&["grub-install", "--target=i386-pc", &device.dev()])?
+                                .status
+                                .success()
+                            {
+                                return Err(format_err!("error installing grub to {}", device.dev()));
+                            }
+                        }
+                        BootMode::Auto => unreachable!("boot mode is resolved before use"),
+                    }
+
+                    // Set up the root partition filesystem, encrypting it first if
+                    // requested.
+                    match encrypt {
+                        EncryptMode::Luks => {
+                            let mapper = Luks::open(
+                                &part_root,
+                                LUKS_MAPPER_NAME,
+                                passphrase.as_ref().expect("passphrase collected above"),
+                            )?;
+                            filesystem.init(&mapper)?;
+                        }
+                        EncryptMode::ZfsNative => {
+                            filesystem.init_with_passphrase(&part_root, passphrase.as_deref())?;
+                        }
+                        EncryptMode::None => {
+                            filesystem.init(&part_root)?;
+                        }
+                    }
+                }
+                ReplaceMode::Alongside => {
+                    // The table belongs to whatever OS is already there, so
+                    // we have to detect the boot/root partitions instead of
+                    // assuming a layout. In EFI mode, also make sure they're
+                    // actually what we expect before reformatting anything.
+                    if boot_mode == BootMode::Efi {
+                        let boot_partition = table.find_esp()?;
+                        let root_partition = find_existing_root(&table)?;
+                        verify_partition_types(boot_partition, root_partition)?;
+                    }
+
+                    // Leave the existing root filesystem and its data in place;
+                    // only reinitialize the boot partitions so the new system has
+                    // somewhere to boot from.
+                    reinit_boot(boot_mode, &device, &table)?;
+                }
+            }
+        }
+        PartitionMode::Manual => {
+            // The user has already partitioned the device themselves; just
+            // format and mount what they told us about.
+            partition_manual(&opt.manual_partition)?;
+        }
+    }
 
-    // Get our partitions.
-    let parts = device.partitions()?;
-    let part_boot = &parts[0];
-    let part_root = &parts[2];
+    // Create the default user and set passwords.
+    user::provision(&user, opt.password_hash, opt.root_password_hash)?;
+
+    Ok(())
+}
 
-    // Get rid of any old partition/filesystem info from the partitions.
-    wipe(part_boot)?;
-    wipe(part_root)?;
+/// Format and mount a user-supplied list of existing partitions, in order.
+fn partition_manual(specs: &[ManualPartitionSpec]) -> Result<()> {
+    for spec in specs {
+        debug!("formatting {:?} as {} ({})", spec.device, spec.fstype, spec.size);
 
-    // Set up the boot partition filesystem.
-    let fat32 = filesystem::FAT32 {};
-    fat32.init(part_boot)?;
+        filesystem::from_str(&spec.fstype)?.init(&Device::from_path(&spec.device))?;
 
-    // Set up the root partition filesystem.
-    filesystem.init(part_root)?;
+        mount_device(&Device::from_path(&spec.device), &spec.mountpoint)?;
+    }
+
+    Ok(())
+}
+
+/// Mount `device` at `mountpoint`, creating the mountpoint first if needed.
+fn mount_device(device: &Device, mountpoint: &Path) -> Result<()> {
+    fs::create_dir_all(mountpoint)?;
+
+    if !exec(&["mount", &device.dev(), &mountpoint.to_string_lossy()])?
+        .status
+        .success()
+    {
+        return Err(format_err!(
+            "error mounting {} at {:?}",
+            device.dev(),
+            mountpoint
+        ));
+    }
+
+    Ok(())
+}
+
+/// Make sure the boot and root partitions are actually the types we expect
+/// before wiping or formatting anything, closing the class of bug where
+/// partitions get mixed up.
+fn verify_partition_types(boot: &device::Partition, root: &device::Partition) -> Result<()> {
+    if !boot.type_guid.eq_ignore_ascii_case(device::ESP_TYPE_GUID) {
+        return Err(format_err!(
+            "boot partition {:?} is not an EFI System partition (found type {})",
+            boot.node,
+            boot.type_guid
+        ));
+    }
+
+    if !root.type_guid.eq_ignore_ascii_case(device::LINUX_TYPE_GUID) {
+        return Err(format_err!(
+            "root partition {:?} is not a Linux filesystem partition (found type {})",
+            root.node,
+            root.type_guid
+        ));
+    }
+
+    Ok(())
+}
+
+/// Find the existing root filesystem's partition on a foreign partition
+/// table, for `Alongside` mode. Prefers whatever's actually mounted at `/mnt`
+/// or `/` (i.e. corroborated by `findmnt`), and falls back to the largest
+/// Linux-type partition when nothing is mounted yet.
+fn find_existing_root(table: &device::PartitionTable) -> Result<&device::Partition> {
+    for mountpoint in ["/mnt", "/"] {
+        if let Ok(existing) = mount::inspect(mountpoint) {
+            if let Ok(source) = existing.resolved_source() {
+                if let Some(partition) = table
+                    .partitions
+                    .iter()
+                    .find(|p| p.node.to_string_lossy() == source)
+                {
+                    return Ok(partition);
+                }
+            }
+        }
+    }
+
+    table.find_largest_linux_partition()
+}
+
+// Reinitialize the boot partitions for an `Alongside` install, leaving the
+// rest of the target device's contents untouched. What that means depends on
+// the firmware mode: EFI has a separate ESP to reformat, while BIOS has no
+// such partition at all and just needs grub reinstalled.
+fn reinit_boot(boot_mode: BootMode, device: &Device, table: &device::PartitionTable) -> Result<()> {
+    match boot_mode {
+        BootMode::Efi => {
+            let part_boot = table.find_esp()?.as_device();
+            let part_root = find_existing_root(table)?.as_device();
+
+            // Mount the existing root filesystem at /mnt so /mnt/boot below
+            // is actually its /boot directory, not an arbitrary empty path;
+            // this also leaves /mnt in place for the later arch-chroot.
+            mount_device(&part_root, Path::new("/mnt"))?;
+
+            // Clear out anything left over in /boot from the previous OS,
+            // then mount the freshly reformatted ESP over it.
+            if !exec(&["rm", "-rf", "/mnt/boot"])?.status.success() {
+                return Err(format_err!("error clearing /mnt/boot"));
+            }
+
+            // Re-run mkfs.vfat on the ESP; this destroys any existing
+            // bootloader entries for the other OS, which is expected to be
+            // reinstalled after reboot.
+            let fat32 = filesystem::FAT32 {};
+            fat32.init(&part_boot)?;
+
+            mount_device(&part_boot, Path::new("/mnt/boot"))?;
+        }
+        BootMode::Bios => {
+            // There's no separate ESP to touch in BIOS mode; the
+            // bios_grub partition (if any) holds core.img directly. Just
+            // reinstall grub so it boots the new system.
+            if !exec(&["grub-install", "--target=i386-pc", &device.dev()])?
+                .status
+                .success()
+            {
+                return Err(format_err!("error installing grub to {}", device.dev()));
+            }
+        }
+        BootMode::Auto => unreachable!("boot mode is resolved before use"),
+    }
 
     Ok(())
 }
@@ -193,45 +519,101 @@ fn wipe(partition: &Device) -> Result<()> {
 }
 
 /// Create partitions.
-fn partition(device: &Device) -> Result<()> {
-    if !exec(&[
-        "parted",
-        "--script",
-        &device.dev(),
-        "--",
-        // Make the partition table.
-        "mklabel",
-        "gpt",
-        // Make the boot partition for EFI.
-        "mkpart",
-        "ESP",
-        "fat32",
-        "1Mib",
-        "2GiB",
-        "set",
-        "1",
-        "boot",
-        "on",
-        // Make a persistent small partition for things like encrypted storage.
-        "mkpart",
-        "primary",
-        "ext4",
-        "2GiB",
-        "3GiB",
-        // Make the ZFS root partition.
-        "mkpart",
-        "primary",
-        "ext4",
-        "3GiB",
-        "100%",
-    ])?
-    .status
-    .success()
-    {
-        return Err(format_err!("error partitioning disk"));
+fn partition(
+    device: &Device,
+    mode: ReplaceMode,
+    boot_mode: BootMode,
+    boot_size: &str,
+    root_size: &str,
+) -> Result<()> {
+    match mode {
+        ReplaceMode::Alongside => {
+            // The target already has a partition table with an OS
+            // installed on it; reuse it rather than destroying it.
+            info!(
+                "alongside mode: reusing existing partition table on {}",
+                device.dev()
+            );
+
+            Ok(())
+        }
+        ReplaceMode::Wipe => {
+            // The first partition is either an EFI System Partition sized
+            // from `boot_size`, or a tiny fixed-size bios_grub partition,
+            // depending on the firmware mode. The persistent partition
+            // starts right after it either way.
+            let (boot_partition_args, persistent_start): (Vec<&str>, String) = match boot_mode {
+                BootMode::Efi => (
+                    vec!["mkpart", "ESP", "fat32", "1Mib", boot_size, "set", "1", "boot", "on"],
+                    boot_size.to_string(),
+                ),
+                BootMode::Bios => (
+                    vec!["mkpart", "BIOS", "1Mib", "3MiB", "set", "1", "bios_grub", "on"],
+                    "3MiB".to_string(),
+                ),
+                BootMode::Auto => unreachable!("boot mode is resolved before use"),
+            };
+
+            // Make a persistent 1GiB partition for things like encrypted
+            // storage, sized off of wherever the boot partition actually
+            // ends rather than a value that only happens to match the
+            // default `--boot-size`.
+            let persistent_end = format_size(parse_size(&persistent_start)? + parse_size("1GiB")?);
+
+            let dev = device.dev();
+            let mut args = vec!["parted", "--script", &dev, "--", "mklabel", "gpt"];
+            args.extend_from_slice(&boot_partition_args);
+            args.extend_from_slice(&[
+                "mkpart",
+                "primary",
+                "ext4",
+                &persistent_start,
+                &persistent_end,
+                // Make the root partition.
+                "mkpart",
+                "primary",
+                "ext4",
+                &persistent_end,
+                root_size,
+            ]);
+
+            if !exec(&args)?.status.success() {
+                return Err(format_err!("error partitioning disk"));
+            }
+
+            Ok(())
+        }
     }
+}
 
-    Ok(())
+/// Parse a `parted`-style absolute size string (e.g. `2GiB`, `512MiB`) into
+/// bytes. Doesn't handle percentages; only used for sizes that mark the
+/// start/end of fixed-size partitions.
+fn parse_size(s: &str) -> Result<u64> {
+    let split_at = s
+        .find(|c: char| c.is_ascii_alphabetic())
+        .ok_or_else(|| format_err!("size {:?} has no unit", s))?;
+    let (number, unit) = s.split_at(split_at);
+
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("parsing size {:?}", s))?;
+
+    let multiplier: u64 = match unit.to_ascii_lowercase().as_str() {
+        "b" => 1,
+        "kib" => 1024,
+        "mib" => 1024u64.pow(2),
+        "gib" => 1024u64.pow(3),
+        "tib" => 1024u64.pow(4),
+        _ => return Err(format_err!("unknown size unit in {:?}", s)),
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Format a byte count as a `parted`-style absolute size string.
+fn format_size(bytes: u64) -> String {
+    format!("{}B", bytes)
 }
 
 fn confirm(title: &str) -> Result<bool> {
@@ -266,6 +648,34 @@ fn exec(cmd: &[&str]) -> Result<process::Output> {
         .with_context(|| format!("{:?} {:?}", cmd, args))?)
 }
 
+// Like `exec`, but first writes `input` to the child's stdin. Used for
+// commands like `cryptsetup` that read secrets from stdin rather than
+// accepting them as arguments.
+fn exec_with_stdin(cmd: &[&str], input: &str) -> Result<process::Output> {
+    debug!("exec: running: {:?}", cmd);
+
+    let (cmd, args) = match cmd {
+        [cmd, args @ ..] => (cmd, args),
+        _ => return Err(format_err!("missing command".to_string())),
+    };
+
+    let mut child = process::Command::new(cmd)
+        .args(args)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("{:?} {:?}", cmd, args))?;
+
+    child
+        .stdin
+        .take()
+        .ok_or_else(|| format_err!("failed to open stdin for {:?}", cmd))?
+        .write_all(input.as_bytes())?;
+
+    Ok(child.wait_with_output()?)
+}
+
 /// Allow the user to interactively select an item.
 fn select(title: &str, items: &[String]) -> Result<usize> {
     Ok(Select::with_theme(&ColorfulTheme::default())
@@ -0,0 +1,97 @@
+use crate::{exec, Device};
+use anyhow::{format_err, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// A single filesystem entry from `findmnt -J -v --output-all`.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct FilesystemInspection {
+    pub(crate) source: String,
+    pub(crate) fstype: String,
+    pub(crate) options: String,
+
+    #[serde(default)]
+    pub(crate) sources: Vec<String>,
+}
+
+impl FilesystemInspection {
+    /// The real block device backing this mount, resolving the bootc-style
+    /// bind/subvolume bracket syntax in `source` (e.g. `/dev/sda2[/subvol]`)
+    /// by falling back to the first entry of `sources`.
+    pub(crate) fn resolved_source(&self) -> Result<&str> {
+        if self.source.contains('[') {
+            return self.sources.first().map(String::as_str).ok_or_else(|| {
+                format_err!(
+                    "source {:?} uses subvolume syntax but sources is empty",
+                    self.source
+                )
+            });
+        }
+
+        Ok(&self.source)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Findmnt {
+    filesystems: Vec<FilesystemInspection>,
+}
+
+/// Inspect whatever is mounted at `path` with `findmnt`.
+pub(crate) fn inspect<T: AsRef<Path>>(path: T) -> Result<FilesystemInspection> {
+    let path = path.as_ref();
+
+    let output = exec(&["findmnt", "-J", "-v", "--output-all", &path.to_string_lossy()])?;
+    if !output.status.success() {
+        return Err(format_err!("nothing mounted at {:?}", path));
+    }
+
+    let parsed: Findmnt = serde_json::from_str(std::str::from_utf8(&output.stdout)?)
+        .with_context(|| format!("parsing findmnt output for {:?}", path))?;
+
+    parsed
+        .filesystems
+        .into_iter()
+        .next()
+        .ok_or_else(|| format_err!("no filesystem reported for {:?}", path))
+}
+
+/// Walk from a partition device node up to its whole-disk parent, e.g.
+/// `/dev/sda2` -> `/dev/sda`, `/dev/nvme0n1p2` -> `/dev/nvme0n1`. Whole-disk
+/// devices are returned unchanged.
+pub(crate) fn find_parent_devices<T: AsRef<Path>>(dev: T) -> Result<Device> {
+    let dev = dev.as_ref();
+    let name = dev
+        .file_name()
+        .ok_or_else(|| format_err!("invalid device path {:?}", dev))?
+        .to_string_lossy();
+
+    let sys_class_block = PathBuf::from("/sys/class/block").join(name.as_ref());
+
+    // Only partitions have a `partition` sysfs attribute reporting their
+    // 1-based partition number; whole disks don't, so there's no parent to
+    // walk up to. The symlink's final path component is always the device's
+    // own name for both cases, so that alone can't tell them apart.
+    if !sys_class_block.join("partition").exists() {
+        return Ok(Device::from_path(PathBuf::from("/dev").join(name.as_ref())));
+    }
+
+    let link = std::fs::read_link(&sys_class_block)
+        .with_context(|| format!("reading {:?}", sys_class_block))?;
+
+    // A partition's /sys/class/block symlink nests under its parent disk's
+    // directory, e.g. `../../devices/.../block/sda/sda2`; the parent disk
+    // name is the second-to-last path component.
+    let components: Vec<&str> = link
+        .components()
+        .filter_map(|c| c.as_os_str().to_str())
+        .collect();
+
+    let parent = components
+        .len()
+        .checked_sub(2)
+        .and_then(|i| components.get(i))
+        .ok_or_else(|| format_err!("could not determine parent disk for {:?}", dev))?;
+
+    Ok(Device::from_path(PathBuf::from("/dev").join(parent)))
+}
@@ -0,0 +1,57 @@
+use anyhow::{format_err, Result};
+use std::{fmt, str::FromStr};
+
+/// Controls how the installer treats any existing contents of the target
+/// device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ReplaceMode {
+    /// Wipe the device and lay down a fresh partition table.
+    Wipe,
+
+    /// Install alongside an existing OS, reusing its partition table and
+    /// only reinitializing `/boot` and `/boot/efi`.
+    Alongside,
+}
+
+impl FromStr for ReplaceMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "wipe" => Self::Wipe,
+            "alongside" => Self::Alongside,
+            _ => return Err(format_err!("unknown replace mode: {}", s)),
+        })
+    }
+}
+
+impl fmt::Display for ReplaceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wipe => write!(f, "wipe"),
+            Self::Alongside => write!(f, "alongside"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_mode_from_str() {
+        assert_eq!(ReplaceMode::from_str("wipe").unwrap(), ReplaceMode::Wipe);
+        assert_eq!(
+            ReplaceMode::from_str("alongside").unwrap(),
+            ReplaceMode::Alongside
+        );
+        assert!(ReplaceMode::from_str("keep").is_err());
+    }
+
+    #[test]
+    fn test_replace_mode_display_round_trips() {
+        for mode in [ReplaceMode::Wipe, ReplaceMode::Alongside] {
+            assert_eq!(ReplaceMode::from_str(&mode.to_string()).unwrap(), mode);
+        }
+    }
+}
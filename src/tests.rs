@@ -24,3 +24,26 @@ fn test_select() {
         1,
     );
 }
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("2GiB").unwrap(), 2 * 1024u64.pow(3));
+    assert_eq!(parse_size("512MiB").unwrap(), 512 * 1024u64.pow(2));
+    assert_eq!(parse_size("1KiB").unwrap(), 1024);
+    assert_eq!(parse_size("100B").unwrap(), 100);
+}
+
+#[test]
+fn test_parse_size_missing_unit() {
+    assert!(parse_size("2").is_err());
+}
+
+#[test]
+fn test_parse_size_unknown_unit() {
+    assert!(parse_size("2XiB").is_err());
+}
+
+#[test]
+fn test_format_size_round_trips_through_parse_size() {
+    assert_eq!(parse_size(&format_size(3 * 1024u64.pow(3))).unwrap(), 3 * 1024u64.pow(3));
+}
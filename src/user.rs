@@ -0,0 +1,103 @@
+use crate::{exec, exec_with_stdin};
+use anyhow::{format_err, Result};
+use dialoguer::Password;
+use sha_crypt::{sha512_simple, Sha512Params};
+
+// Path the new system is mounted at; account creation runs inside a chroot
+// here rather than on the host running the installer.
+const CHROOT: &str = "/mnt";
+
+/// Create the default user and set the root password on the installed
+/// system, inside its chroot.
+///
+/// `password_hash`/`root_password_hash` should be pre-hashed crypt strings
+/// (as accepted by `chpasswd -e`/`usermod -p`) when passed non-interactively;
+/// if either is missing, the user is prompted for a plaintext password which
+/// is hashed locally before being written.
+pub(crate) fn provision(
+    username: &str,
+    password_hash: Option<String>,
+    root_password_hash: Option<String>,
+) -> Result<()> {
+    let password_hash = match password_hash {
+        Some(h) => h,
+        None => hash_password(&prompt_password(&format!("Password for {}", username))?)?,
+    };
+
+    let root_password_hash = match root_password_hash {
+        Some(h) => h,
+        None => hash_password(&prompt_password("Root password")?)?,
+    };
+
+    create_user(username)?;
+    enable_wheel_sudo()?;
+    set_user_password(username, &password_hash)?;
+    set_root_password(&root_password_hash)?;
+
+    Ok(())
+}
+
+fn prompt_password(prompt: &str) -> Result<String> {
+    Ok(Password::new()
+        .with_prompt(prompt)
+        .with_confirmation("Confirm password", "Passwords didn't match")
+        .interact()?)
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    sha512_simple(password, &Sha512Params::default())
+        .map_err(|_| format_err!("error hashing password"))
+}
+
+fn create_user(username: &str) -> Result<()> {
+    if !exec(&["arch-chroot", CHROOT, "useradd", "-m", "-G", "wheel", username])?
+        .status
+        .success()
+    {
+        return Err(format_err!("error creating user {}", username));
+    }
+
+    Ok(())
+}
+
+fn enable_wheel_sudo() -> Result<()> {
+    if !exec(&[
+        "arch-chroot",
+        CHROOT,
+        "sed",
+        "-i",
+        "s/^# %wheel ALL=(ALL) ALL/%wheel ALL=(ALL) ALL/",
+        "/etc/sudoers",
+    ])?
+    .status
+    .success()
+    {
+        return Err(format_err!("error enabling wheel group sudo"));
+    }
+
+    Ok(())
+}
+
+fn set_user_password(username: &str, hash: &str) -> Result<()> {
+    if !exec(&["arch-chroot", CHROOT, "usermod", "-p", hash, username])?
+        .status
+        .success()
+    {
+        return Err(format_err!("error setting password for {}", username));
+    }
+
+    Ok(())
+}
+
+fn set_root_password(hash: &str) -> Result<()> {
+    let input = format!("root:{}\n", hash);
+
+    if !exec_with_stdin(&["arch-chroot", CHROOT, "chpasswd", "-e"], &input)?
+        .status
+        .success()
+    {
+        return Err(format_err!("error setting root password"));
+    }
+
+    Ok(())
+}